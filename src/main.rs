@@ -3,20 +3,24 @@ use anyhow::Result;
 use futures::stream::{self, StreamExt};
 use reqwest::Client;
 use serde::Deserialize;
-use serde_json::Value;
-use std::fs;
 use std::fs::OpenOptions;
-use std::io::{self, BufRead, BufReader, Read, Write, Seek, SeekFrom};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::env;
 use std::error::Error;
-use std::fs::{File, write, remove_file};
+use std::fs::File;
 use tokio::time::{sleep, Duration};
-use flate2::read::GzDecoder;
 use std::collections::HashMap;
 use url::Url;
 use readability_rust::{Readability, ReadabilityOptions};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::process::{Command, Stdio};
+use publicsuffix::{List, Psl};
+use std::sync::OnceLock;
+use percent_encoding::percent_decode_str;
+use whatlang::detect as detect_lang;
+use std::time::SystemTime;
+use std::sync::Arc;
+use async_compression::tokio::bufread::GzipDecoder;
+use tokio_util::io::StreamReader;
+use tokio::io::AsyncWriteExt;
 
 
 #[derive(Debug, Deserialize)]
@@ -41,33 +45,45 @@ struct DomainRecord {
 	n_hosts: usize,
 }
 
-/// Extracts the domain (first subdomain + TLD) from a URL.
+/// Bundled Mozilla Public Suffix List, parsed once and reused for every
+/// `extract_domain` call so registrable-domain lookups don't depend on a
+/// network fetch or a hand-maintained multi-TLD table.
+fn public_suffix_list() -> &'static List {
+	static LIST: OnceLock<List> = OnceLock::new();
+	LIST.get_or_init(|| {
+		include_str!("../assets/public_suffix_list.dat")
+			.parse()
+			.expect("bundled public suffix list should parse")
+	})
+}
+
+/// Canonicalizes a hostname to lowercase ASCII punycode (IDNA), the same
+/// representation `url::Host::parse` produces, so hosts spelled with
+/// Swedish characters (å/ä/ö) and hosts already stored as `xn--…` compare
+/// equal as HashMap keys. Returns `None` for empty hosts and hosts with
+/// invalid domain characters instead of passing them through verbatim.
+fn normalize_host(host: &str) -> Option<String> {
+	let decoded = percent_decode_str(host.trim()).decode_utf8().ok()?;
+	idna::domain_to_ascii(&decoded.to_lowercase()).ok().filter(|h| !h.is_empty())
+}
+
+/// Extracts the registrable domain (public suffix + one label) from a URL.
 /// Example: "https://news.google.com" -> "google.com"
+/// Honors multi-label suffixes like "pp.se" or "co.uk" via the PSL instead
+/// of a hardcoded table, so e.g. "foo.pp.se" and "bar.pp.se" stay distinct.
+///
+/// `public_suffix_list()` is a trimmed excerpt (see the header of
+/// `assets/public_suffix_list.dat`): only the generic TLDs, the se/no/dk/fi
+/// sections and a few uk/au/jp rules are listed. For any other TLD, `publicsuffix`
+/// falls back to its implicit `*` rule and this returns a *wrong*
+/// registrable domain (e.g. "foo.com.br" -> "com.br") rather than `None`
+/// — it is only safe to call this for targets the bundled list covers.
 pub fn extract_domain(url_str: &str) -> Option<String> {
 	let parsed = Url::parse(url_str).ok()?;
-	let host = parsed.host_str()?;
-
-	// Split host by '.' and collect
-	let parts: Vec<&str> = host.split('.').collect();
-
-	// Handle short or weird hostnames safely
-	if parts.len() < 2 {
-		return Some(host.to_string());
-	}
-
-	// Handle common multi-level TLDs like .co.uk, .com.au, etc.
-	let multi_tlds = ["co.uk", "org.uk", "gov.uk", "com.au", "co.jp"];
-	let last_two = parts[parts.len() - 2..].join(".");
+	let host = normalize_host(parsed.host_str()?)?;
 
-	if multi_tlds.contains(&last_two.as_str()) && parts.len() >= 3 {
-		// example.co.uk → take first subdomain + 2-part TLD → example.co.uk
-		let domain = parts[parts.len() - 3..].join(".");
-		return Some(domain);
-	}
-
-	// Default case → take last two segments (domain + TLD)
-	let domain = parts[parts.len() - 2..].join(".");
-	Some(domain)
+	let domain = public_suffix_list().domain(host.as_bytes())?;
+	std::str::from_utf8(domain.as_bytes()).ok().map(str::to_string)
 }
 
 fn reverse_domain(host_rev: &str) -> String {
@@ -76,7 +92,7 @@ fn reverse_domain(host_rev: &str) -> String {
 	reversed_parts.join(".")
 }
 
-fn read_se_domains(path: &str) -> Result<Vec<DomainRecord>, Box<dyn Error>> {
+fn read_domain_allowlist(path: &str, cfg: &TargetConfig) -> Result<Vec<DomainRecord>, Box<dyn Error>> {
 	let file = File::open(path)?;
 	let reader = BufReader::new(file);
 
@@ -141,14 +157,22 @@ fn read_se_domains(path: &str) -> Result<Vec<DomainRecord>, Box<dyn Error>> {
 			}
 		};
 
-		// filter .se domains (host_rev ending with ".se")
-		if host_rev.starts_with("se.") {
+		// filter to the target country (host_rev starting with e.g. "se.")
+		if host_rev.starts_with(&cfg.host_rev_prefix) {
+			let host = match normalize_host(&reverse_domain(&host_rev)) {
+				Some(h) => h,
+				None => {
+					eprintln!("warning: skipping line {} with invalid host {:?}", line_no + 1, host_rev);
+					continue;
+				}
+			};
+
 			results.push(DomainRecord {
 				harmonicc_pos,
 				harmonicc_val,
 				pr_pos,
 				pr_val,
-				host: reverse_domain(&host_rev),
+				host,
 				n_hosts,
 			});
 		}
@@ -157,32 +181,6 @@ fn read_se_domains(path: &str) -> Result<Vec<DomainRecord>, Box<dyn Error>> {
 	Ok(results)
 }
 
-/// Write the filtered `.se` domains to a new file in the same format.
-fn write_se_domains(path: &str, records: &[DomainRecord]) -> Result<(), Box<dyn Error>> {
-	let mut file = File::create(path)?;
-
-	// optional header (comment line like the source file)
-	writeln!(
-		file,
-		"#harmonicc_pos\t#harmonicc_val\t#pr_pos\t#pr_val\t#host\t#n_hosts"
-	)?;
-
-	for rec in records {
-		writeln!(
-			file,
-			"{}\t{:.7E}\t{}\t{:.18}\t{}\t{}",
-			rec.harmonicc_pos,
-			rec.harmonicc_val,
-			rec.pr_pos,
-			rec.pr_val,
-			reverse_domain(&rec.host),
-			rec.n_hosts
-		)?;
-	}
-
-	Ok(())
-}
-
 fn records_to_map(records: &[DomainRecord]) -> HashMap<String, &DomainRecord> {
 	let mut map = HashMap::new();
 	for rec in records {
@@ -196,10 +194,88 @@ fn de_from_str<'de, D>(deserializer: D) -> Result<u64, D::Error> where D: serde:
 	s.parse::<u64>().map_err(serde::de::Error::custom)
 }
 
-fn build_index_url(cc_crawl: &String) -> String {
+/// Per-country/language crawl target. Threading this through
+/// `build_index_url`, `read_domain_allowlist` and `read_warc_headers`
+/// turns the `DomainRecord`/allowlist machinery into a generic
+/// registrable-domain filter instead of Sweden-specific code, so the same
+/// binary can harvest e.g. `.no`/`no`, `.dk`/`da`, `.fi`/`fi`.
+#[derive(Debug, Clone)]
+struct TargetConfig {
+	tld: String,
+	host_rev_prefix: String,
+	lang_code: String,
+	lang_confidence: f64,
+	min_text_len: usize,
+}
+
+impl TargetConfig {
+	fn swedish() -> Self {
+		TargetConfig {
+			tld: "se".to_string(),
+			host_rev_prefix: "se.".to_string(),
+			lang_code: "sv".to_string(),
+			lang_confidence: 0.8,
+			min_text_len: 100,
+		}
+	}
+
+	/// Parses `--tld`, `--host-prefix`, `--lang`, `--confidence` and
+	/// `--min-len` flags out of the CLI args, falling back to the Swedish
+	/// defaults for anything not given. `--host-prefix` defaults to
+	/// `"<tld>."` rather than staying pinned to `"se."`, so passing
+	/// `--tld no` without an explicit `--host-prefix` still filters the
+	/// allowlist against the right country instead of silently matching
+	/// nothing.
+	fn from_args(args: &[String]) -> Self {
+		let mut cfg = TargetConfig::swedish();
+		let mut host_prefix_override = None;
+		let mut iter = args.iter();
+
+		while let Some(flag) = iter.next() {
+			let Some(value) = iter.next() else { break };
+			match flag.as_str() {
+				"--tld" => cfg.tld = value.clone(),
+				"--host-prefix" => host_prefix_override = Some(value.clone()),
+				"--lang" => cfg.lang_code = value.clone(),
+				"--confidence" => {
+					if let Ok(v) = value.parse() {
+						cfg.lang_confidence = v;
+					}
+				}
+				"--min-len" => {
+					if let Ok(v) = value.parse() {
+						cfg.min_text_len = v;
+					}
+				}
+				// read separately by `ranking_file_flag`
+				"--ranking-file" => {}
+				_ => eprintln!("warning: ignoring unknown flag {:?}", flag),
+			}
+		}
+
+		cfg.host_rev_prefix = host_prefix_override.unwrap_or_else(|| format!("{}.", cfg.tld));
+		cfg
+	}
+}
+
+/// Scans `args` for `--ranking-file <path>`, independent of the
+/// `TargetConfig` flags, since the ranking file is a CLI/filesystem
+/// concern rather than part of the crawl target itself.
+fn ranking_file_flag(args: &[String]) -> Option<String> {
+	let mut iter = args.iter();
+	while let Some(flag) = iter.next() {
+		let Some(value) = iter.next() else { break };
+		if flag == "--ranking-file" {
+			return Some(value.clone());
+		}
+	}
+	None
+}
+
+fn build_index_url(cc_crawl: &String, cfg: &TargetConfig) -> String {
 	format!(
-		"https://index.commoncrawl.org/{}-index?url=*.se/*&output=json",
-		cc_crawl
+		"https://index.commoncrawl.org/{}-index?url=*.{}/*&output=json",
+		cc_crawl, cfg.tld
 	)
 }
 
@@ -210,66 +286,199 @@ fn build_output_file(cc_crawl: &String) -> String {
 	)
 }
 
-async fn fetch_and_write(client: &reqwest::Client, rec: &CcRecord, writer: &mut std::io::BufWriter<std::fs::File>) -> anyhow::Result<()> {
-	let offset = rec.offset;
-	let length = rec.length;
-	let range = format!("bytes={}-{}", offset, offset + length - 1);
-	let warc_url = format!("https://data.commoncrawl.org/{}", rec.filename);
+fn checkpoint_path(output_path: &str) -> String {
+	format!("{}.done", output_path)
+}
 
-	let resp = client.get(&warc_url).header("Range", range).send().await?;
-	if !resp.status().is_success() {
-		eprintln!("[WARN] Skipping {} (HTTP {})", warc_url, resp.status());
-		eprintln!("sleeping a bit before continuing.");
-		sleep(Duration::from_millis(1000)).await;
-		return Ok(());
+/// Stable identity for a `CcRecord` within a crawl: the WARC file plus the
+/// byte offset it starts at. Used both as the checkpoint line format and
+/// the in-memory done-set key.
+fn record_key(rec: &CcRecord) -> String {
+	format!("{}\t{}", rec.filename, rec.offset)
+}
+
+/// Loads the set of record keys already written in a prior run, so a
+/// crashed or rate-limited crawl can resume without refetching them.
+/// A missing checkpoint file just means a fresh crawl.
+fn load_checkpoint(path: &str) -> io::Result<std::collections::HashSet<String>> {
+	let mut done = std::collections::HashSet::new();
+
+	let file = match File::open(path) {
+		Ok(f) => f,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(done),
+		Err(e) => return Err(e),
+	};
+
+	for line in BufReader::new(file).lines() {
+		done.insert(line?);
 	}
 
-	let bytes = resp.bytes().await?;
-	let mut gz = GzDecoder::new(&bytes[..]);
+	Ok(done)
+}
+
+/// Appends a single record key to the checkpoint file, flushing so the
+/// done-set on disk reflects reality even if the process is killed right
+/// after.
+fn append_checkpoint(file: &mut File, key: &str) -> io::Result<()> {
+	writeln!(file, "{}", key)?;
+	file.flush()
+}
 
-	let mut decompressed = Vec::new();
-	gz.read_to_end(&mut decompressed)?; // decompress fully into memory
+/// How many WARC range requests run concurrently against data.commoncrawl.org.
+const FETCH_CONCURRENCY: usize = 8;
+/// Attempts for a single record before giving up and moving on.
+const MAX_FETCH_ATTEMPTS: u32 = 5;
 
-	writer.write_all(&decompressed)?;
-	writer.flush()?;
+/// Parses a `Retry-After` header, which the HTTP spec allows as either a
+/// delay in seconds or an HTTP-date to wait until.
+fn retry_after_delay(resp: &reqwest::Response) -> Option<Duration> {
+	let value = resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
 
-	println!("[OK] Wrote uncompressed WARC for {}", rec.url);
-	Ok(())
+	if let Ok(secs) = value.parse::<u64>() {
+		return Some(Duration::from_secs(secs));
+	}
+
+	let at = httpdate::parse_http_date(value).ok()?;
+	Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Exponential backoff (0.5s, 1s, 2s, 4s, ...) with a little jitter so a
+/// burst of retrying records doesn't all retry in lockstep.
+fn backoff_delay(attempt: u32) -> Duration {
+	let base_ms = 500u64.saturating_mul(1u64 << attempt.min(6));
+	let jitter_ms: u64 = rand::random::<u64>() % 250;
+	Duration::from_millis(base_ms + jitter_ms)
+}
+
+/// Scratch path a record's decompressed body is staged at before being
+/// appended to the real output, so the writer lock below only ever guards
+/// a local file-to-file copy rather than the network fetch/decompression.
+fn scratch_path(rec: &CcRecord) -> std::path::PathBuf {
+	std::env::temp_dir().join(format!("warc_{}_{}.tmp", rec.filename.replace('/', "_"), rec.offset))
+}
+
+/// Fetches a record's WARC range, retrying 429/503 responses according to
+/// `Retry-After` (or an exponential backoff when the header is absent) and
+/// giving up after `MAX_FETCH_ATTEMPTS`. `multiple_members` decodes
+/// concatenated gzip members, which WARC files commonly contain.
+///
+/// Returns `Ok(true)` once the record's body has actually been appended to
+/// `writer`, `Ok(false)` for a non-success status that isn't worth
+/// retrying (e.g. 404), and `Err` if every retry was exhausted or the
+/// request, decompression or a write failed — callers must only checkpoint
+/// a record on `Ok(true)`; after an `Err` the record still needs fetching
+/// on the next run.
+async fn fetch_and_write(
+	client: &reqwest::Client,
+	rec: &CcRecord,
+	writer: &tokio::sync::Mutex<tokio::io::BufWriter<tokio::fs::File>>,
+) -> anyhow::Result<bool> {
+	let range = format!("bytes={}-{}", rec.offset, rec.offset + rec.length - 1);
+	let warc_url = format!("https://data.commoncrawl.org/{}", rec.filename);
+
+	for attempt in 0..MAX_FETCH_ATTEMPTS {
+		let resp = client.get(&warc_url).header("Range", range.clone()).send().await?;
+
+		if resp.status().is_success() {
+			let body = StreamReader::new(
+				resp.bytes_stream()
+					.map(|chunk| chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e))),
+			);
+			let mut decoder = GzipDecoder::new(body);
+			decoder.multiple_members(true);
+
+			// Decode into a scratch file without holding the writer lock, so
+			// this record's network fetch and decompression run concurrently
+			// with other in-flight records under `buffer_unordered`. The
+			// lock is only taken for the short final copy into `writer`.
+			let scratch = scratch_path(rec);
+			let mut scratch_file = tokio::fs::File::create(&scratch).await?;
+			tokio::io::copy(&mut decoder, &mut scratch_file).await?;
+			scratch_file.flush().await?;
+			drop(scratch_file);
+
+			let mut scratch_reader = tokio::fs::File::open(&scratch).await?;
+			{
+				let mut writer = writer.lock().await;
+				tokio::io::copy(&mut scratch_reader, &mut *writer).await?;
+				writer.flush().await?;
+			}
+			let _ = tokio::fs::remove_file(&scratch).await;
+
+			println!("[OK] Wrote uncompressed WARC for {}", rec.url);
+			return Ok(true);
+		}
+
+		if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+			|| resp.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE
+		{
+			let wait = retry_after_delay(&resp).unwrap_or_else(|| backoff_delay(attempt));
+			eprintln!("[WARN] {} -> HTTP {}, retrying in {:?}", warc_url, resp.status(), wait);
+			sleep(wait).await;
+			continue;
+		}
+
+		eprintln!("[WARN] Skipping {} (HTTP {})", warc_url, resp.status());
+		return Ok(false);
+	}
+
+	anyhow::bail!("giving up on {} after {} attempts", warc_url, MAX_FETCH_ATTEMPTS);
+}
+
+/// Splits a WARC record body into its inner HTTP response headers and the
+/// HTML that follows them. WARC records for `response` entries wrap the
+/// captured HTTP exchange verbatim, so the body itself starts with another
+/// header block terminated by a blank line.
+fn strip_http_headers(body: &[u8]) -> Option<String> {
+	let text = String::from_utf8_lossy(body);
+	let split = text.find("\r\n\r\n").map(|i| i + 4)
+		.or_else(|| text.find("\n\n").map(|i| i + 2))?;
+	Some(text[split..].to_string())
 }
 
-fn parse() -> Result<(), Box<dyn std::error::Error>> {
+/// Reads exactly `len` bytes of a record's body. `extract_article_text`
+/// requires the complete HTML as one `&str` (both `readability_rust` and
+/// `whatlang` take a whole document, not a stream), so there is no way to
+/// bound peak memory per record here without changing those dependencies
+/// — this reads the body directly into one `len`-sized buffer rather than
+/// pretending a reusable scratch buffer bounds anything it doesn't.
+fn read_body(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+	let mut body = vec![0u8; len];
+	reader.read_exact(&mut body)?;
+	Ok(body)
+}
 
-	let html = fs::read_to_string("ww2.html")?;
-	let base_url = Url::parse("https://en.wikipedia.org/wiki/World_War_II")?;
+/// Maps whatlang's ISO 639-3 output to the ISO 639-1 code this crate's
+/// JSONL output (and `TargetConfig::lang_code`) has always used, falling
+/// back to the 639-3 code for languages without a common two-letter form.
+fn to_iso639_1(code_639_3: &str) -> &str {
+	match code_639_3 {
+		"swe" => "sv",
+		"nob" | "nno" => "no",
+		"dan" => "da",
+		"fin" => "fi",
+		other => other,
+	}
+}
 
-	// set up readability options (you can tweak these)
+/// Runs readability extraction and language detection on a record's HTML,
+/// in-process, replacing the old `python3 html2text.py` round trip.
+fn extract_article_text(html: &str) -> Option<(String, f64, String)> {
 	let options = ReadabilityOptions {
 		..Default::default()
 	};
+	let mut readability = Readability::new(html, Some(options)).ok()?;
+	let article = readability.parse()?;
+	let text = article.text_content?;
 
-	// parse article
-	let mut readability = Readability::new(&html, Some(options))?;
-	if let Some(article) = readability.parse() {
-		println!("Title: {}", article.title.unwrap());
-		println!("\nPlain text:\n{}\n", article.text_content.unwrap());
-	} else {
-		println!("❌ No article content extracted.");
-	}
-
-	return Ok(());
+	let info = detect_lang(&text)?;
+	Some((to_iso639_1(info.lang().code()).to_string(), info.confidence(), text))
 }
 
-fn read_warc_headers(file_path: &str) -> io::Result<()> {
-	let mut file = File::open(file_path)?;
+fn read_warc_headers(file_path: &str, cfg: &TargetConfig) -> io::Result<()> {
+	let file = File::open(file_path)?;
 	let mut reader = BufReader::new(&file);
 
-	// create unique tmp filename
-	let unique_id = SystemTime::now()
-		.duration_since(UNIX_EPOCH)
-		.unwrap()
-		.as_nanos();
-	let tmp_path = format!("/tmp/warc_{}.html", unique_id);
-
 	let out_path = format!("{}.json", file_path);
 	let mut out_file = OpenOptions::new()
 		.create(true)
@@ -286,7 +495,6 @@ fn read_warc_headers(file_path: &str) -> io::Result<()> {
 			let bytes = reader.read_line(&mut line)?;
 			if bytes == 0 {
 				// EOF
-				remove_file(&tmp_path);
 				return Ok(());
 			}
 			if line.trim().is_empty() {
@@ -299,83 +507,87 @@ fn read_warc_headers(file_path: &str) -> io::Result<()> {
 			break;
 		}
 
-		// Extract Content-Length if present
+		// Extract Content-Length and WARC-Target-URI if present
 		let mut content_length = 0usize;
+		let mut url = String::new();
 		for h in &headers {
 			if let Some(value) = h.strip_prefix("Content-Length: ") {
 				content_length = value.parse::<usize>().unwrap_or(0);
 			}
+			if let Some(value) = h.strip_prefix("WARC-Target-URI: ") {
+				url = value.to_string();
+			}
 		}
 
 		if content_length > 0 {
-			// Skip the content body
-			let mut content = vec![0u8; content_length + 4];
-			reader.read_exact(&mut content)?;
-
-			write(&tmp_path, &content)?;
-
-			// run python script
-			let output = Command::new("python3")
-				.arg("html2text.py")
-				.arg(&tmp_path)
-				.stdout(Stdio::piped())
-				.stderr(Stdio::null())
-				.output()?;
-
-			if output.status.success() {
-				let json_output = String::from_utf8_lossy(&output.stdout);
-
-				// parse json
-				if let Ok(parsed) = serde_json::from_str::<Value>(&json_output) {
-					let lang = parsed.get("lang").and_then(Value::as_str).unwrap_or("");
-					let lang_prob = parsed.get("lang_prob").and_then(Value::as_str)
-						.and_then(|s| s.parse::<f64>().ok())
-						.unwrap_or(0.0);
-					let text = parsed.get("text").and_then(Value::as_str).unwrap_or("");
-
-					if lang == "sv" && lang_prob > 0.8 && text.len() > 100 {
-						let compact = serde_json::to_string(&parsed)?;
-						writeln!(out_file, "{}", compact)?;
+			let content = read_body(&mut reader, content_length + 4)?;
+
+			if let Some(html) = strip_http_headers(&content) {
+				if let Some((lang, lang_prob, text)) = extract_article_text(&html) {
+					// cfg.lang_code is the ISO 639-1 code (e.g. "sv"); extract_article_text
+					// already maps whatlang's ISO 639-3 output down to it.
+					if lang == cfg.lang_code && lang_prob > cfg.lang_confidence && text.len() > cfg.min_text_len {
+						// Keep emitting the same JSONL shape the python path did:
+						// lang_prob as a string, not a JSON number.
+						let record = serde_json::json!({
+							"lang": lang,
+							"lang_prob": lang_prob.to_string(),
+							"text": text,
+							"url": url,
+						});
+						writeln!(out_file, "{}", record)?;
 						println!("Wrote to output");
 					}
 				}
 			}
 		}
 	}
-	remove_file(&tmp_path);
-	return Ok(());
+	Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
 
-	let file_path = "/mnt/data_ssd/CC-MAIN-2013-20.warc";
-	read_warc_headers(file_path);
-	return Ok(());
-
-	parse();
-	return Ok(());
-
-
-	let path = "se_domains.txt";
-	let se_domains = read_se_domains(path).unwrap();
-	let se_map = records_to_map(&se_domains);
-
 	let args: Vec<String> = env::args().collect();
 
 	if args.len() < 2 {
-		eprintln!("Usage: {} <argument>", args[0]);
+		eprintln!("Usage:");
+		eprintln!("  {} <cc_crawl> [--tld se] [--host-prefix <tld>.] [--ranking-file <tld>_domains.txt] [--lang sv] [--confidence 0.8] [--min-len 100]", args[0]);
+		eprintln!("  {} extract <warc-file> [--lang sv] [--confidence 0.8] [--min-len 100]", args[0]);
 		return Ok(());
 	}
 
+	// `extract` re-runs the WARC -> JSONL pass over an already-downloaded file,
+	// independent of querying a new crawl.
+	if args[1] == "extract" {
+		let Some(file_path) = args.get(2) else {
+			eprintln!("Usage: {} extract <warc-file> [flags]", args[0]);
+			return Ok(());
+		};
+		let cfg = TargetConfig::from_args(&args[3..]);
+		return read_warc_headers(file_path, &cfg).map_err(Into::into);
+	}
+
+	let cfg = TargetConfig::from_args(&args[2..]);
+
+	let path = ranking_file_flag(&args[2..]).unwrap_or_else(|| format!("{}_domains.txt", cfg.tld));
+	let domain_allowlist = read_domain_allowlist(&path, &cfg).unwrap();
+	if domain_allowlist.is_empty() {
+		eprintln!(
+			"[WARN] allowlist {:?} yielded 0 hosts for host-prefix {:?} — crawl will skip every record",
+			path, cfg.host_rev_prefix
+		);
+	}
+	let allowed_domains = records_to_map(&domain_allowlist);
+
 	let cc_crawl = &args[1];
 
 	let client = Client::builder()
 		.user_agent("CommonCrawlResearchBot/1.0 (contact: josefcullhed@gmail.com)")
 		.build()?;
-	println!("[INFO] Querying Common Crawl index: {}", build_index_url(cc_crawl));
+	println!("[INFO] Querying Common Crawl index: {}", build_index_url(cc_crawl, &cfg));
 
-	let resp = client.get(build_index_url(cc_crawl)).send().await?;
+	let resp = client.get(build_index_url(cc_crawl, &cfg)).send().await?;
 	let text = resp.text().await?;
 	let mut records = Vec::new();
 
@@ -388,28 +600,151 @@ async fn main() -> Result<()> {
 		}
 	}
 
-	println!("[INFO] Found {} .se records", records.len());
+	println!("[INFO] Found {} .{} records", records.len(), cfg.tld);
+
+	let output_path = build_output_file(cc_crawl);
+	let done_path = checkpoint_path(&output_path);
+	let done = load_checkpoint(&done_path)?;
+	println!("[INFO] {} records already done, resuming", done.len());
 
-	let file = OpenOptions::new()
+	let file = tokio::fs::OpenOptions::new()
 		.create(true)
 		.append(true)
-		.open(build_output_file(cc_crawl))?;
+		.open(&output_path)
+		.await?;
 
-	let mut writer = std::io::BufWriter::new(file);
+	let writer = Arc::new(tokio::sync::Mutex::new(tokio::io::BufWriter::new(file)));
 
-	for rec in records {
-		if let Some(domain) = extract_domain(&rec.url) {
-			if let Some(record) = se_map.get(&domain) {
-				if let Err(e) = fetch_and_write(&client, &rec, &mut writer).await {
-					eprintln!("[WARN] {} -> {}", rec.url, e);
+	let done_file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&done_path)?;
+	let done_file = Arc::new(tokio::sync::Mutex::new(done_file));
+
+	stream::iter(records)
+		.filter(|rec| {
+			let already_done = done.contains(&record_key(rec));
+			async move { !already_done }
+		})
+		.map(|rec| {
+			let client = &client;
+			let allowed_domains = &allowed_domains;
+			let writer = Arc::clone(&writer);
+			let done_file = Arc::clone(&done_file);
+			async move {
+				if let Some(domain) = extract_domain(&rec.url) {
+					if allowed_domains.contains_key(&domain) {
+						match fetch_and_write(client, &rec, &writer).await {
+							// Only a record that was actually written gets checkpointed;
+							// a 429/503 that exhausted every retry must stay un-done so
+							// it gets refetched on resume instead of being skipped forever.
+							Ok(true) => {
+								let mut done_file = done_file.lock().await;
+								if let Err(e) = append_checkpoint(&mut done_file, &record_key(&rec)) {
+									eprintln!("[WARN] checkpoint write failed for {}: {}", rec.url, e);
+								}
+							}
+							Ok(false) => {}
+							Err(e) => eprintln!("[WARN] {} -> {}", rec.url, e),
+						}
+					} else {
+						println!("skipped {}", rec.url);
+					}
 				}
-			} else {
-				println!("skipped {}", rec.url);
 			}
-		} else {
-		}
-	}
+		})
+		.buffer_unordered(FETCH_CONCURRENCY)
+		.collect::<Vec<()>>()
+		.await;
 
 	println!("[DONE] Saved raw WARC records to {}", build_output_file(cc_crawl));
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn extract_domain_honors_multi_label_se_suffix() {
+		// "pp.se" is a multi-label PSL rule, so the registrable domain is the
+		// label in front of it, not just the last two labels.
+		assert_eq!(extract_domain("https://foo.pp.se"), Some("foo.pp.se".to_string()));
+		assert_eq!(extract_domain("https://bar.pp.se"), Some("bar.pp.se".to_string()));
+		assert_ne!(extract_domain("https://foo.pp.se"), extract_domain("https://bar.pp.se"));
+	}
+
+	#[test]
+	fn extract_domain_matches_unicode_and_punycode_hosts() {
+		// "mörby.se" and its punycode form must extract to the same
+		// registrable domain, since they're the same host on the wire.
+		assert_eq!(
+			extract_domain("https://www.mörby.se"),
+			extract_domain("https://www.xn--mrby-5qa.se"),
+		);
+	}
+
+	#[test]
+	fn normalize_host_lowercases_and_percent_decodes() {
+		assert_eq!(normalize_host("EXAMPLE.SE"), normalize_host("example.se"));
+		assert_eq!(normalize_host("exa%6dple.se").as_deref(), Some("example.se"));
+	}
+
+	#[test]
+	fn normalize_host_rejects_invalid_domains() {
+		assert_eq!(normalize_host(""), None);
+	}
+
+	#[test]
+	fn to_iso639_1_maps_known_whatlang_codes() {
+		assert_eq!(to_iso639_1("swe"), "sv");
+		assert_eq!(to_iso639_1("dan"), "da");
+		assert_eq!(to_iso639_1("fin"), "fi");
+	}
+
+	#[test]
+	fn to_iso639_1_falls_back_to_639_3_for_unmapped_codes() {
+		assert_eq!(to_iso639_1("eng"), "eng");
+	}
+
+	#[test]
+	fn record_key_combines_filename_and_offset() {
+		let rec = CcRecord {
+			url: "https://example.se".to_string(),
+			filename: "crawl-data/CC-MAIN-2024-10/segments/foo.warc.gz".to_string(),
+			offset: 12345,
+			length: 678,
+		};
+		assert_eq!(record_key(&rec), "crawl-data/CC-MAIN-2024-10/segments/foo.warc.gz\t12345");
+	}
+
+	#[test]
+	fn backoff_delay_grows_with_attempt_and_stays_jittered() {
+		let first = backoff_delay(0);
+		let third = backoff_delay(2);
+		// Base doubles per attempt (500ms, 1s, 2s, ...); jitter is at most 250ms,
+		// so attempt 2's base alone already exceeds attempt 0's base + jitter.
+		assert!(first >= Duration::from_millis(500));
+		assert!(first < Duration::from_millis(750));
+		assert!(third >= Duration::from_millis(2000));
+		assert!(third < Duration::from_millis(2250));
+	}
+
+	#[test]
+	fn retry_after_delay_parses_numeric_seconds() {
+		let http_resp = http::Response::builder()
+			.status(429)
+			.header(reqwest::header::RETRY_AFTER, "120")
+			.body(bytes::Bytes::new())
+			.unwrap();
+		let resp = reqwest::Response::from(http_resp);
+		assert_eq!(retry_after_delay(&resp), Some(Duration::from_secs(120)));
+	}
+
+	#[test]
+	fn retry_after_delay_returns_none_without_header() {
+		let http_resp = http::Response::builder().status(503).body(bytes::Bytes::new()).unwrap();
+		let resp = reqwest::Response::from(http_resp);
+		assert_eq!(retry_after_delay(&resp), None);
+	}
+}